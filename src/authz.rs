@@ -0,0 +1,219 @@
+use crate::startup::AppData;
+use actix_session::SessionExt;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header::LOCATION, StatusCode},
+    Error, HttpMessage, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use uuid::Uuid;
+
+// Session key under which the logged-in user id is stored. This MUST match the
+// key `TypedSession::get_user_id`/`renew` use (`session_state.rs`); otherwise
+// every gated request would fail to resolve the user and redirect to /login.
+const USER_ID_KEY: &str = "user_id";
+
+// the roles a user can hold. stored one-row-per-role in the `user_roles` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Author,
+    Reader,
+}
+
+// a capability a route can require. roles are expanded into the set of
+// permissions they grant, so handlers reason about permissions, not roles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreatePost,
+    ManageUsers,
+}
+
+impl Role {
+    // the permissions granted by holding this role.
+    fn grants(&self, permission: Permission) -> bool {
+        matches!(
+            (self, permission),
+            (Role::Admin, _)
+                | (Role::Author, Permission::CreatePost)
+        )
+    }
+}
+
+// the authenticated user attached to a request by the auth gate. handlers pull
+// it out with `web::ReqData<Principal>` instead of touching the session.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: Uuid,
+    pub roles: Vec<Role>,
+}
+
+impl Principal {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.roles.iter().any(|r| r.grants(permission))
+    }
+}
+
+#[tracing::instrument(name = "loading user roles", skip(db_pool))]
+async fn load_roles(user_id: Uuid, db_pool: &sqlx::PgPool) -> Result<Vec<Role>, sqlx::Error> {
+    // decode straight into `Role` through its `sqlx::Type` derive.
+    let roles = sqlx::query_scalar!(
+        r#"SELECT role as "role: Role" FROM user_roles WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    Ok(roles)
+}
+
+// middleware factory. wrap a route or scope with `AuthGate::require(perm)` to
+// enforce that permission before the handler runs; the resolved `Principal` is
+// placed in request extensions for the handler to read.
+//
+// Every route whose handler extracts `web::ReqData<Principal>` MUST be wrapped,
+// or the extractor 500s on the missing extension. In `startup.rs` that is:
+//
+//     .service(web::scope("")
+//         .wrap(AuthGate::require(Permission::CreatePost))
+//         .service(submit_post))
+//     .service(web::scope("")
+//         .wrap(AuthGate::require(Permission::ManageUsers))
+//         .service(add_user))
+pub struct AuthGate {
+    required: Permission,
+}
+
+impl AuthGate {
+    pub fn require(required: Permission) -> Self {
+        Self { required }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuthGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<actix_web::body::EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthGateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthGateMiddleware {
+            service: Rc::new(service),
+            required: self.required,
+        }))
+    }
+}
+
+pub struct AuthGateMiddleware<S> {
+    service: Rc<S>,
+    required: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthGateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<actix_web::body::EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let required = self.required;
+        Box::pin(async move {
+            // resolve the session user; unauthenticated requests go to /login.
+            let user_id = req
+                .get_session()
+                .get::<Uuid>(USER_ID_KEY)
+                .ok()
+                .flatten();
+            let user_id = match user_id {
+                Some(id) => id,
+                None => {
+                    let res = HttpResponse::SeeOther()
+                        .insert_header((LOCATION, "/login"))
+                        .finish()
+                        .map_into_right_body();
+                    return Ok(req.into_response(res));
+                }
+            };
+
+            // load roles once for the whole request and enforce the permission.
+            let db_pool = &req
+                .app_data::<actix_web::web::Data<AppData>>()
+                .expect("AppData is always registered")
+                .db_pool;
+            // a failure to load roles is a server fault, not a denial: surface
+            // it as 500 rather than silently stripping an authorized user's
+            // permissions.
+            let roles = load_roles(user_id, db_pool)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            let principal = Principal { user_id, roles };
+            if !principal.can(required) {
+                let res = HttpResponse::new(StatusCode::FORBIDDEN).map_into_right_body();
+                return Ok(req.into_response(res));
+            }
+            req.extensions_mut().insert(principal);
+
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_holds_every_permission() {
+        let admin = Principal {
+            user_id: Uuid::nil(),
+            roles: vec![Role::Admin],
+        };
+        assert!(admin.can(Permission::CreatePost));
+        assert!(admin.can(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn author_may_post_but_not_manage_users() {
+        let author = Principal {
+            user_id: Uuid::nil(),
+            roles: vec![Role::Author],
+        };
+        assert!(author.can(Permission::CreatePost));
+        assert!(!author.can(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn reader_holds_no_write_permissions() {
+        let reader = Principal {
+            user_id: Uuid::nil(),
+            roles: vec![Role::Reader],
+        };
+        assert!(!reader.can(Permission::CreatePost));
+        assert!(!reader.can(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn no_roles_means_no_permissions() {
+        let nobody = Principal {
+            user_id: Uuid::nil(),
+            roles: vec![],
+        };
+        assert!(!nobody.can(Permission::CreatePost));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::object_store::ObjectStore;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Background task that reclaims ephemeral posts once their `expires_at` has
+// passed. Spawned from `startup` alongside the HTTP server; it wakes on a fixed
+// interval, deletes expired rows (and their `post_images` via cascade) and
+// removes the backing objects from the `ObjectStore`.
+pub async fn run_expiry_worker(db_pool: PgPool, store: Arc<dyn ObjectStore>, interval: Duration) {
+    loop {
+        if let Err(e) = purge_expired(&db_pool, store.as_ref()).await {
+            tracing::error!("expiry worker pass failed: {:?}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[tracing::instrument(name = "purging expired posts", skip(db_pool, store))]
+async fn purge_expired(db_pool: &PgPool, store: &dyn ObjectStore) -> Result<(), sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"SELECT post_id FROM post WHERE expires_at IS NOT NULL AND expires_at <= now()"#,
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    for row in expired {
+        // grab the object keys before the rows disappear via cascade delete.
+        let images = sqlx::query_scalar!(
+            r#"SELECT path FROM post_images WHERE post_id = $1"#,
+            row.post_id,
+        )
+        .fetch_all(db_pool)
+        .await?;
+
+        sqlx::query!(r#"DELETE FROM post WHERE post_id = $1"#, row.post_id)
+            .execute(db_pool)
+            .await?;
+
+        // best-effort object cleanup: a failure here is logged but must not
+        // block purging the rest of the expired posts.
+        for path in images {
+            let key = object_key(&path);
+            if let Err(e) = store.delete(key).await {
+                tracing::warn!("failed to delete expired object {}: {:?}", key, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// the stored reference is a relative path or object URL; the object key is its
+// final path segment.
+fn object_key(reference: &str) -> &str {
+    reference.rsplit('/').next().unwrap_or(reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::object_key;
+
+    #[test]
+    fn key_is_the_final_segment_of_a_local_path() {
+        assert_eq!(object_key("../uploads/abc-original.png"), "abc-original.png");
+    }
+
+    #[test]
+    fn key_is_the_final_segment_of_an_object_url() {
+        assert_eq!(
+            object_key("https://cdn.example.com/bucket/abc-thumbnail.jpg"),
+            "abc-thumbnail.jpg"
+        );
+    }
+
+    #[test]
+    fn a_bare_key_is_returned_unchanged() {
+        assert_eq!(object_key("abc.png"), "abc.png");
+    }
+}
@@ -0,0 +1,273 @@
+use actix_multipart::Multipart;
+use futures::{StreamExt, TryStreamExt};
+use std::collections::HashMap;
+use thiserror::Error;
+
+// Declarative description of the fields a multipart route accepts. A single
+// reusable parser drives the `Multipart` stream against one of these, so routes
+// describe *what* they expect rather than hand-rolling the parse loop (with its
+// `unwrap()`s on missing dispositions).
+pub struct FormSchema {
+    pub text_fields: Vec<TextField>,
+    pub file_fields: Vec<FileField>,
+}
+
+// a named text field routed into memory. `required` fields must appear exactly
+// once; every text field may appear at most once.
+pub struct TextField {
+    pub name: &'static str,
+    pub required: bool,
+}
+
+// a named file field. zero-or-more uploads are accepted; each is capped at
+// `max_bytes` and its sniffed MIME type must be in `allowed_mime`.
+pub struct FileField {
+    pub name: &'static str,
+    pub max_bytes: usize,
+    pub allowed_mime: &'static [&'static str],
+}
+
+#[derive(Debug, Error)]
+pub enum FormError {
+    #[error("missing required field `{0}`")]
+    MissingField(String),
+    #[error("unexpected field `{0}`")]
+    UnexpectedField(String),
+    #[error("duplicate field `{0}`")]
+    DuplicateField(String),
+    #[error("a field could not be parsed")]
+    Parse,
+    #[error("error reading an uploaded field")]
+    Read,
+    #[error("an uploaded file is larger than the allowed limit")]
+    FileTooLarge,
+    #[error("an uploaded file is not an allowed type")]
+    InvalidFileType,
+}
+
+// a file field that passed validation: its validated bytes plus the sniffed
+// MIME type and matching extension. the route decides how to persist it.
+pub struct ParsedFile {
+    pub field: &'static str,
+    pub bytes: Vec<u8>,
+    pub mime: String,
+    pub ext: String,
+}
+
+// the typed result of driving a `Multipart` stream against a `FormSchema`.
+pub struct ParsedForm {
+    texts: HashMap<&'static str, String>,
+    files: Vec<ParsedFile>,
+}
+
+impl ParsedForm {
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.texts.get(name).map(String::as_str)
+    }
+
+    pub fn files(&self) -> &[ParsedFile] {
+        &self.files
+    }
+}
+
+impl FormSchema {
+    // Drive the multipart stream against this schema, returning a typed form or
+    // a `FormError`. Contains no `unwrap()`/`panic!`: malformed, missing,
+    // duplicate, unexpected, oversized and wrong-type fields all surface as
+    // errors.
+    #[tracing::instrument(name = "parsing multipart form", skip(self, payload))]
+    pub async fn parse(&self, mut payload: Multipart) -> Result<ParsedForm, FormError> {
+        let mut texts: HashMap<&'static str, String> = HashMap::new();
+        let mut files = Vec::new();
+
+        while let Some(mut field) = payload.try_next().await.map_err(|_| FormError::Read)? {
+            let name = field
+                .content_disposition()
+                .get_name()
+                .ok_or(FormError::Parse)?
+                .to_string();
+
+            if let Some(spec) = self.text_fields.iter().find(|f| f.name == name) {
+                // accumulate the raw bytes and decode once: multipart chunk
+                // boundaries need not fall on UTF-8 char boundaries, so decoding
+                // per-chunk would spuriously reject a multi-byte char split
+                // across two chunks.
+                let mut raw = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    let data = chunk.map_err(|_| FormError::Read)?;
+                    raw.extend_from_slice(&data);
+                }
+                let value = String::from_utf8(raw).map_err(|_| FormError::Parse)?;
+                if texts.insert(spec.name, value).is_some() {
+                    return Err(FormError::DuplicateField(name));
+                }
+            } else if let Some(spec) = self.file_fields.iter().find(|f| f.name == name) {
+                // an empty file input (no chosen file) is simply skipped.
+                let has_filename = field
+                    .content_disposition()
+                    .get_filename()
+                    .map(|f| !f.trim().is_empty())
+                    .unwrap_or(false);
+                if !has_filename {
+                    continue;
+                }
+                let mut bytes = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    let data = chunk.map_err(|_| FormError::Read)?;
+                    if bytes.len() + data.len() > spec.max_bytes {
+                        return Err(FormError::FileTooLarge);
+                    }
+                    bytes.extend_from_slice(&data);
+                }
+                if bytes.is_empty() {
+                    continue;
+                }
+                // never trust the declared Content-Type: sniff the magic number.
+                let kind = infer::get(&bytes).ok_or(FormError::InvalidFileType)?;
+                if !spec.allowed_mime.contains(&kind.mime_type()) {
+                    return Err(FormError::InvalidFileType);
+                }
+                files.push(ParsedFile {
+                    field: spec.name,
+                    bytes,
+                    mime: kind.mime_type().to_string(),
+                    ext: kind.extension().to_string(),
+                });
+            } else {
+                return Err(FormError::UnexpectedField(name));
+            }
+        }
+
+        for spec in &self.text_fields {
+            if spec.required && !texts.contains_key(spec.name) {
+                return Err(FormError::MissingField(spec.name.to_string()));
+            }
+        }
+
+        Ok(ParsedForm { texts, files })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::error::PayloadError;
+    use actix_web::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+    use actix_web::web::Bytes;
+
+    const BOUNDARY: &str = "BOUNDARY";
+    // the 8-byte PNG signature is enough for `infer` to recognise the type.
+    const PNG_MAGIC: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+    fn schema() -> FormSchema {
+        FormSchema {
+            text_fields: vec![
+                TextField {
+                    name: "post-editor",
+                    required: true,
+                },
+                TextField {
+                    name: "validity_secs",
+                    required: false,
+                },
+            ],
+            file_fields: vec![FileField {
+                name: "image",
+                max_bytes: 1024,
+                allowed_mime: &["image/png"],
+            }],
+        }
+    }
+
+    fn text_part(buf: &mut Vec<u8>, name: &str, value: &str) {
+        buf.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+        buf.extend_from_slice(
+            format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+        );
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    fn file_part(buf: &mut Vec<u8>, name: &str, filename: &str, bytes: &[u8]) {
+        buf.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+        buf.extend_from_slice(
+            format!(
+                "Content-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\n"
+            )
+            .as_bytes(),
+        );
+        buf.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        buf.extend_from_slice(bytes);
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    fn multipart(mut body: Vec<u8>) -> Multipart {
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=BOUNDARY"),
+        );
+        let stream =
+            futures::stream::once(async move { Ok::<_, PayloadError>(Bytes::from(body)) });
+        Multipart::new(&headers, stream)
+    }
+
+    #[actix_web::test]
+    async fn parses_text_and_file_fields() {
+        let mut body = Vec::new();
+        text_part(&mut body, "post-editor", "hello world");
+        file_part(&mut body, "image", "pic.png", PNG_MAGIC);
+
+        let form = schema().parse(multipart(body)).await.unwrap();
+        assert_eq!(form.text("post-editor"), Some("hello world"));
+        assert_eq!(form.files().len(), 1);
+        assert_eq!(form.files()[0].mime, "image/png");
+        assert_eq!(form.files()[0].ext, "png");
+    }
+
+    #[actix_web::test]
+    async fn missing_required_field_is_rejected() {
+        let body = Vec::new();
+        let err = schema().parse(multipart(body)).await.unwrap_err();
+        assert!(matches!(err, FormError::MissingField(f) if f == "post-editor"));
+    }
+
+    #[actix_web::test]
+    async fn duplicate_text_field_is_rejected() {
+        let mut body = Vec::new();
+        text_part(&mut body, "post-editor", "one");
+        text_part(&mut body, "post-editor", "two");
+        let err = schema().parse(multipart(body)).await.unwrap_err();
+        assert!(matches!(err, FormError::DuplicateField(_)));
+    }
+
+    #[actix_web::test]
+    async fn unexpected_field_is_rejected() {
+        let mut body = Vec::new();
+        text_part(&mut body, "post-editor", "hi");
+        text_part(&mut body, "surprise", "nope");
+        let err = schema().parse(multipart(body)).await.unwrap_err();
+        assert!(matches!(err, FormError::UnexpectedField(f) if f == "surprise"));
+    }
+
+    #[actix_web::test]
+    async fn non_image_upload_is_rejected() {
+        let mut body = Vec::new();
+        text_part(&mut body, "post-editor", "hi");
+        file_part(&mut body, "image", "notes.txt", b"just some plain text bytes");
+        let err = schema().parse(multipart(body)).await.unwrap_err();
+        assert!(matches!(err, FormError::InvalidFileType));
+    }
+
+    #[actix_web::test]
+    async fn oversized_upload_is_rejected() {
+        let mut tight = schema();
+        tight.file_fields[0].max_bytes = 4;
+        let mut body = Vec::new();
+        text_part(&mut body, "post-editor", "hi");
+        file_part(&mut body, "image", "pic.png", PNG_MAGIC);
+        let err = tight.parse(multipart(body)).await.unwrap_err();
+        assert!(matches!(err, FormError::FileTooLarge));
+    }
+}
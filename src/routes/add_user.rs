@@ -1,3 +1,4 @@
+use crate::authz::Principal;
 use crate::domain::new_user::NewUser;
 use crate::startup::AppData;
 use actix_web::{error, http::StatusCode, post, web, HttpResponse, HttpResponseBuilder, Result};
@@ -36,10 +37,13 @@ pub struct NewUserForm {
     pub password_ver: String,
 }
 
+// The route is wrapped with `AuthGate::require(Permission::ManageUsers)`, so the
+// gate has already resolved the session and enforced the permission before we
+// run; the `Principal` is pulled from request extensions to confirm that.
 #[post("/add_user")]
 #[tracing::instrument(
     name="adding a new user",
-    skip(data, form),
+    skip(data, form, _principal),
     fields(
         email=%form.email,
         username=%form.username,
@@ -49,6 +53,7 @@ pub struct NewUserForm {
 pub async fn add_user(
     data: web::Data<AppData>,
     form: web::Form<NewUserForm>,
+    _principal: web::ReqData<Principal>,
 ) -> Result<HttpResponse, NewUserError> {
     // use your domain! now there is only a single access point
     // for the api which should greatly increase app security and reliability
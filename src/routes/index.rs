@@ -0,0 +1,24 @@
+use crate::routes::fetch_feed;
+use crate::startup::AppData;
+use crate::utils::e500;
+use actix_web::{get, web, HttpResponse};
+
+// Renders the feed. Posts are loaded via `fetch_feed`, which excludes rows whose
+// `expires_at` has passed, so expired ephemeral posts stop rendering the instant
+// they lapse rather than lingering until the next purge sweep.
+#[get("/")]
+#[tracing::instrument(name = "rendering the index", skip(data))]
+pub async fn index(data: web::Data<AppData>) -> Result<HttpResponse, actix_web::Error> {
+    let posts = fetch_feed(&data.db_pool).await.map_err(e500)?;
+    let items: String = posts
+        .iter()
+        .map(|post| format!("<li>{}</li>", post.body))
+        .collect();
+    let body = format!(
+        "<!doctype html><html><head><title>showsk</title></head>\
+         <body><ul>{items}</ul></body></html>"
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
@@ -1,6 +1,8 @@
 use crate::{
+    authz::Principal,
     domain::post::NewPost,
-    session_state::TypedSession,
+    multipart_form::{FileField, FormError, FormSchema, TextField},
+    object_store::ObjectStore,
     startup::AppData,
     utils::{e500, get_username},
 };
@@ -10,13 +12,68 @@ use actix_web::{
     Result,
 };
 use chrono::Utc;
-use futures::{StreamExt, TryStreamExt};
+use image::ImageFormat;
 use sqlx::PgPool;
-use std::fs;
-use std::io::Write;
+use std::io::Cursor;
 use thiserror::Error;
 use uuid::Uuid;
 
+// image MIME types we are willing to store. we sniff the leading bytes of each
+// upload rather than trusting the client-declared Content-Type. webp is
+// accepted and its original is stored as-is; only the downscaled *variants* are
+// transcoded to a guaranteed-encodable format (see `store_image`), since the
+// `image` crate's webp encoder is lossless-only and often unavailable.
+const ALLOWED_IMAGE_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+// downscaled sizes we generate for an accepted upload, in addition to the
+// untouched original, whenever the source is wider than the target. each is
+// bounded by width, preserving aspect ratio.
+const THUMBNAIL_WIDTH: u32 = 320;
+const DISPLAY_WIDTH: u32 = 1024;
+
+// the size bucket a stored image row represents.
+pub enum ImageVariant {
+    Original,
+    Display,
+    Thumbnail,
+}
+
+impl ImageVariant {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImageVariant::Original => "original",
+            ImageVariant::Display => "display",
+            ImageVariant::Thumbnail => "thumbnail",
+        }
+    }
+}
+
+// one stored image variant belonging to a post, destined for a `post_images`
+// row. multiple variants (and multiple images) can belong to a single post.
+pub struct NewPostImage {
+    pub reference: String,
+    pub ordinal: i32,
+    pub width: i32,
+    pub height: i32,
+    pub variant: ImageVariant,
+}
+
+// durations a submission may choose to live for before being purged, plus an
+// implicit "never" (no `validity_secs`, or a value of 0).
+const ALLOWED_VALIDITY_SECS: [i64; 3] = [
+    60 * 60,          // an hour
+    60 * 60 * 24,     // a day
+    60 * 60 * 24 * 7, // a week
+];
+
+// the fully-parsed submission: the post body, every image variant we generated
+// for it, and an optional time-to-live in seconds.
+pub struct BuiltPost {
+    pub post: NewPost,
+    pub images: Vec<NewPostImage>,
+    pub validity_secs: Option<i64>,
+}
+
 // custom error handler for the route
 // TODO: switch to a better error writing framework (rather than roll your own)
 #[derive(Debug, Error)]
@@ -31,6 +88,26 @@ pub enum NewPostError {
     ParseError,
     #[error("User does not have permission to make post")]
     PermissionDenied,
+    #[error("Uploaded file is larger than the allowed limit")]
+    FileTooLarge,
+    #[error("Uploaded file is not a supported image type")]
+    InvalidFileType,
+}
+
+// map the shared parser's errors onto this route's error type so `?` works
+// directly on `FormSchema::parse`.
+impl From<FormError> for NewPostError {
+    fn from(e: FormError) -> Self {
+        match e {
+            FormError::FileTooLarge => NewPostError::FileTooLarge,
+            FormError::InvalidFileType => NewPostError::InvalidFileType,
+            FormError::Read => NewPostError::FileUploadError,
+            FormError::MissingField(_)
+            | FormError::UnexpectedField(_)
+            | FormError::DuplicateField(_)
+            | FormError::Parse => NewPostError::ParseError,
+        }
+    }
 }
 
 impl error::ResponseError for NewPostError {
@@ -45,33 +122,27 @@ impl error::ResponseError for NewPostError {
             NewPostError::ParseError => StatusCode::BAD_REQUEST,
             NewPostError::FileUploadPathError => StatusCode::INTERNAL_SERVER_ERROR,
             NewPostError::PermissionDenied => StatusCode::FORBIDDEN,
+            NewPostError::FileTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            NewPostError::InvalidFileType => StatusCode::BAD_REQUEST,
         }
     }
 }
 
 #[post("/submit_post")]
-#[tracing::instrument(name = "adding a new post", skip(session, payload, data))]
+#[tracing::instrument(name = "adding a new post", skip(principal, payload, data))]
 pub async fn submit_post(
     payload: Multipart,
     data: web::Data<AppData>,
-    session: TypedSession,
+    principal: web::ReqData<Principal>,
 ) -> Result<HttpResponse, NewPostError> {
-    // protect the route and get username to add to post
-    let userid = if let Some(uid) = session
-        .get_user_id()
-        .map_err(|_| NewPostError::PermissionDenied)?
-    {
-        uid
-    } else {
-        return Ok(HttpResponse::SeeOther()
-            .insert_header((LOCATION, "/login"))
-            .finish());
-    };
+    // the auth gate has already resolved the session and checked that this
+    // principal holds `Permission::CreatePost`, so we can trust the id here.
+    let userid = principal.user_id;
 
     // use your domain! now there is only a single access point
     // for the api which should greatly increase app security and reliability
-    let new_post = build_post(payload, &data.upload_path).await?;
-    insert_post(userid, &new_post, &data.db_pool)
+    let built = build_post(payload, data.store.as_ref(), data.max_upload_bytes).await?;
+    insert_post(userid, &built, &data.db_pool)
         .await
         .map_err(|_| NewPostError::QueryError)?;
     // all done redirect to index
@@ -81,84 +152,242 @@ pub async fn submit_post(
 }
 
 // Take the payload from a multipart/form-data post submission and turn it into
-// a valid post
-// TODO: allow for multiple image uploads?
-#[tracing::instrument(name = "adding a new post", skip(payload))]
-pub async fn build_post(mut payload: Multipart, u_path: &str) -> Result<NewPost, NewPostError> {
-    // prep upload dest and create our text payload
-    let uppath = std::env::current_dir().unwrap().join(&u_path);
-    if !std::path::Path::new(&uppath).is_dir() {
-        std::fs::create_dir_all(&uppath.to_str().unwrap())
-            .map_err(|_| NewPostError::FileUploadPathError)?;
+// a valid post with every uploaded image (and its generated variants).
+#[tracing::instrument(name = "adding a new post", skip(payload, store))]
+pub async fn build_post(
+    payload: Multipart,
+    store: &dyn ObjectStore,
+    max_upload_bytes: usize,
+) -> Result<BuiltPost, NewPostError> {
+    // describe the submission declaratively and let the shared parser drive the
+    // stream; it validates names/sizes/types and hands back a typed form with
+    // no panics on malformed input.
+    let schema = FormSchema {
+        text_fields: vec![
+            TextField {
+                name: "post-editor",
+                required: true,
+            },
+            TextField {
+                name: "validity_secs",
+                required: false,
+            },
+        ],
+        file_fields: vec![FileField {
+            name: "image",
+            max_bytes: max_upload_bytes,
+            allowed_mime: &ALLOWED_IMAGE_TYPES,
+        }],
+    };
+    let form = schema.parse(payload).await?;
+
+    let body = form.text("post-editor").unwrap_or_default().to_string();
+    let validity_secs = parse_validity(form.text("validity_secs"))?;
+
+    // persist every uploaded image (original + downscaled variants), ordered by
+    // the sequence they appeared in the form.
+    let mut images = Vec::new();
+    for (ordinal, file) in form.files().iter().enumerate() {
+        let format =
+            ImageFormat::from_mime_type(&file.mime).ok_or(NewPostError::InvalidFileType)?;
+        images.extend(store_image(store, &file.bytes, format, &file.ext, ordinal as i32).await?);
     }
-    fs::create_dir_all(&uppath.to_str().unwrap()).map_err(|_| NewPostError::FileUploadError)?;
-    let mut text_body = Vec::new();
-    let mut filepath = "".to_string();
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_type = field.content_disposition();
-        // check disposition for field name
-        // TODO: more dynamic condition checking
-        if content_type.get_name().unwrap() == "post-editor" {
-            // have to iterate over our text body byte stream
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                let body_str =
-                    String::from_utf8(data.to_vec()).map_err(|_| NewPostError::ParseError)?;
-                text_body.push(body_str);
-            }
-        }
-        // same as above but for the other field
-        else if content_type.get_name().unwrap() == "image"
-            && !content_type.get_filename().unwrap().trim().is_empty()
-        {
-            let filename = format!(
-                "{}-{}",
-                Uuid::new_v4(),
-                sanitize_filename::sanitize(content_type.get_filename().unwrap())
-            );
-
-            // absolute path
-            let upload_str = format!("{}/{}", uppath.to_str().unwrap(), filename);
-            // relative filepath
-            filepath = format!("../{}/{}", u_path, filename);
-
-            let mut f = web::block(move || std::fs::File::create(upload_str))
-                .await
-                .map_err(|_| NewPostError::FileUploadError)?
-                .unwrap();
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                f = web::block(move || f.write_all(&data).map(|_| f))
-                    .await
-                    .map_err(|_| NewPostError::FileUploadError)?
-                    .unwrap();
-            }
+
+    let post = NewPost::new(body, String::new()).map_err(|_| NewPostError::ParseError)?;
+    Ok(BuiltPost {
+        post,
+        images,
+        validity_secs,
+    })
+}
+
+// Validate an optional `validity_secs` value against the offered durations; an
+// absent, empty or zero value means the post never expires.
+fn parse_validity(raw: Option<&str>) -> Result<Option<i64>, NewPostError> {
+    let raw = raw.unwrap_or("").trim();
+    if raw.is_empty() {
+        return Ok(None);
+    }
+    let secs: i64 = raw.parse().map_err(|_| NewPostError::ParseError)?;
+    match secs {
+        0 => Ok(None),
+        s if ALLOWED_VALIDITY_SECS.contains(&s) => Ok(Some(s)),
+        _ => Err(NewPostError::ParseError),
+    }
+}
+
+// Persist one uploaded image as its original plus a 1024px display copy and a
+// 320px thumbnail, returning a `NewPostImage` row per stored object. A variant
+// is only produced when the source is wider than its target width, so a narrow
+// source may yield the original alone.
+//
+// Variants are always re-encoded to PNG or JPEG -- the only formats the `image`
+// crate is guaranteed to encode. Re-encoding a source format back to itself
+// (notably WebP, whose encoder is lossless-only and often unavailable) can fail
+// with `Unsupported` *after* validation, so we never do it. Animated GIFs are
+// flattened to their first frame, which is the frame `load_from_memory` yields.
+#[tracing::instrument(name = "storing image variants", skip(store, bytes))]
+async fn store_image(
+    store: &dyn ObjectStore,
+    bytes: &[u8],
+    format: ImageFormat,
+    ext: &str,
+    ordinal: i32,
+) -> Result<Vec<NewPostImage>, NewPostError> {
+    let decoded = image::load_from_memory(bytes).map_err(|_| NewPostError::InvalidFileType)?;
+
+    // JPEG sources stay JPEG; everything else (PNG, GIF) encodes to PNG so alpha
+    // and indexed palettes survive the downscale.
+    let (variant_format, variant_ext) = match format {
+        ImageFormat::Jpeg => (ImageFormat::Jpeg, "jpg"),
+        _ => (ImageFormat::Png, "png"),
+    };
+
+    let mut rows = Vec::new();
+    // original, untouched bytes keep the source format/extension.
+    rows.push((ImageVariant::Original, bytes.to_vec(), ext, decoded.width(), decoded.height()));
+    // downscaled variants. `thumbnail` would *upscale* a source narrower than
+    // the target, producing a variant larger and blurrier than the original, so
+    // we skip any variant whose target width the source already meets.
+    for (variant, width) in [
+        (ImageVariant::Display, DISPLAY_WIDTH),
+        (ImageVariant::Thumbnail, THUMBNAIL_WIDTH),
+    ] {
+        if decoded.width() <= width {
+            continue;
         }
+        let scaled = decoded.thumbnail(width, u32::MAX);
+        let mut buf = Cursor::new(Vec::new());
+        scaled
+            .write_to(&mut buf, variant_format)
+            .map_err(|_| NewPostError::FileUploadError)?;
+        rows.push((variant, buf.into_inner(), variant_ext, scaled.width(), scaled.height()));
+    }
+
+    let mut stored = Vec::with_capacity(rows.len());
+    for (variant, data, ext, width, height) in rows {
+        let key = format!("{}-{}.{}", Uuid::new_v4(), variant.as_str(), ext);
+        let reference = store
+            .put(&key, data)
+            .await
+            .map_err(|_| NewPostError::FileUploadError)?
+            .reference;
+        stored.push(NewPostImage {
+            reference,
+            ordinal,
+            width: width as i32,
+            height: height as i32,
+            variant,
+        });
     }
-    let body = text_body.join(" ");
-    NewPost::new(body, filepath).map_err(|_| NewPostError::ParseError)
+    Ok(stored)
 }
 
-// send the post to the db.
-// TODO: add user_id once you've figured out session data
-#[tracing::instrument(name = "adding a new post", skip(db_pool, post))]
-pub async fn insert_post(user: Uuid, post: &NewPost, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+// send the post and its image rows to the db in a single transaction, so a
+// post is never left half-written if an image insert fails.
+#[tracing::instrument(name = "adding a new post", skip(db_pool, built))]
+pub async fn insert_post(user: Uuid, built: &BuiltPost, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    let post_id = Uuid::new_v4();
+    // a TTL is turned into an absolute instant so the expiry worker and the
+    // feed query can both reason about it without knowing the submission time.
+    let expires_at = built
+        .validity_secs
+        .map(|secs| Utc::now() + chrono::Duration::seconds(secs));
     sqlx::query!(
         r#"
-        INSERT INTO post (post_id, body, image, timestmp, user_id)
+        INSERT INTO post (post_id, body, timestmp, user_id, expires_at)
         VALUES ($1, $2, $3, $4, $5)
         "#,
-        Uuid::new_v4(),
-        post.body.as_ref(),
-        post.image.path,
+        post_id,
+        built.post.body.as_ref(),
         Utc::now(),
         user,
+        expires_at,
     )
-    .execute(db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         tracing::error!("Failed to insert query: {:?}", e);
         e
     })?;
+
+    for image in &built.images {
+        sqlx::query!(
+            r#"
+            INSERT INTO post_images (image_id, post_id, path, ordinal, width, height, variant)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::new_v4(),
+            post_id,
+            image.reference,
+            image.ordinal,
+            image.width,
+            image.height,
+            image.variant.as_str(),
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to insert image row: {:?}", e);
+            e
+        })?;
+    }
+
+    tx.commit().await?;
     Ok(())
 }
+
+// a single row of the index/feed.
+pub struct FeedPost {
+    pub post_id: Uuid,
+    pub body: String,
+}
+
+// Fetch the posts shown on the index, newest first. Ephemeral posts whose
+// `expires_at` has passed are filtered out here so they stop being served the
+// instant they expire, rather than lingering until the next purge sweep.
+#[tracing::instrument(name = "fetching the feed", skip(db_pool))]
+pub async fn fetch_feed(db_pool: &PgPool) -> Result<Vec<FeedPost>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        FeedPost,
+        r#"
+        SELECT post_id, body
+        FROM post
+        WHERE expires_at IS NULL OR expires_at > now()
+        ORDER BY timestmp DESC
+        "#,
+    )
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validity_absent_or_empty_means_never() {
+        assert_eq!(parse_validity(None).unwrap(), None);
+        assert_eq!(parse_validity(Some("")).unwrap(), None);
+        assert_eq!(parse_validity(Some("   ")).unwrap(), None);
+    }
+
+    #[test]
+    fn validity_zero_means_never() {
+        assert_eq!(parse_validity(Some("0")).unwrap(), None);
+    }
+
+    #[test]
+    fn validity_accepts_allow_listed_durations() {
+        assert_eq!(parse_validity(Some("3600")).unwrap(), Some(3600));
+        assert_eq!(parse_validity(Some(" 86400 ")).unwrap(), Some(86400));
+    }
+
+    #[test]
+    fn validity_rejects_off_list_and_garbage_values() {
+        assert!(parse_validity(Some("42")).is_err());
+        assert!(parse_validity(Some("not-a-number")).is_err());
+    }
+}
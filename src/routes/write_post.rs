@@ -0,0 +1,30 @@
+use actix_web::{get, HttpResponse};
+
+// Renders the new-post editor. The form submits multipart/form-data to
+// /submit_post with the `post-editor` text field, zero-or-more `image` files,
+// and the `validity_secs` TTL control below.
+#[get("/write_post")]
+#[tracing::instrument(name = "rendering the post editor")]
+pub async fn write_post() -> HttpResponse {
+    let body = r#"<!doctype html>
+<html>
+<head><title>new post</title></head>
+<body>
+  <form action="/submit_post" method="post" enctype="multipart/form-data">
+    <textarea name="post-editor" placeholder="what's on your mind?"></textarea>
+    <input type="file" name="image" accept="image/*" multiple>
+    <label for="validity_secs">expires after</label>
+    <select name="validity_secs" id="validity_secs">
+      <option value="0" selected>never</option>
+      <option value="3600">1 hour</option>
+      <option value="86400">1 day</option>
+      <option value="604800">1 week</option>
+    </select>
+    <button type="submit">post</button>
+  </form>
+</body>
+</html>"#;
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}